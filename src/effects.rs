@@ -0,0 +1,89 @@
+use strum::IntoEnumIterator;
+
+use crate::frame::{usage_to_key_name, CustomFrame, KeyName, KEY_COUNT};
+use smart_leds::RGB8;
+
+/// Produces one lighting frame per `run_effect` tick from the HID usage codes that
+/// were freshly pressed this tick, mirroring how firmware keyboards drive
+/// `smart_leds` animations off a key-event stream.
+pub trait Effect {
+    fn next_frame(&mut self, pressed: &[u8]) -> CustomFrame;
+}
+
+fn scale(color: RGB8, level: u8) -> RGB8 {
+    RGB8 {
+        r: (color.r as u16 * level as u16 / 0xff) as u8,
+        g: (color.g as u16 * level as u16 / 0xff) as u8,
+        b: (color.b as u16 * level as u16 / 0xff) as u8,
+    }
+}
+
+/// A pressed key flashes at full brightness, then fades back to off over `decay_ticks`
+/// frames (a per-key intensity buffer decremented each tick).
+pub struct ReactiveTyping {
+    color: RGB8,
+    decay_ticks: u8,
+    // frames each key has left to stay lit
+    intensity: [u8; KEY_COUNT],
+}
+
+impl ReactiveTyping {
+    pub fn new(color: RGB8, decay_ticks: u8) -> Self {
+        ReactiveTyping {
+            color,
+            decay_ticks: decay_ticks.max(1),
+            intensity: [0; KEY_COUNT],
+        }
+    }
+}
+
+impl Effect for ReactiveTyping {
+    fn next_frame(&mut self, pressed: &[u8]) -> CustomFrame {
+        for &usage in pressed {
+            if let Some(key) = usage_to_key_name(usage) {
+                self.intensity[key as usize] = self.decay_ticks;
+            }
+        }
+
+        let mut frame = CustomFrame::new();
+        for key in KeyName::iter() {
+            let remaining = self.intensity[key as usize];
+            if remaining > 0 {
+                let level = (remaining as u16 * 0xff / self.decay_ticks as u16) as u8;
+                frame.set_key(key, scale(self.color, level));
+            }
+            self.intensity[key as usize] = remaining.saturating_sub(1);
+        }
+        frame
+    }
+}
+
+/// A software wave that sweeps `color` across the keyboard, slot by slot, something
+/// the firmware presets can't do while a custom color is also in use.
+pub struct Wave {
+    color: RGB8,
+    speed: u8,
+    phase: u8,
+}
+
+impl Wave {
+    pub fn new(color: RGB8, speed: u8) -> Self {
+        Wave {
+            color,
+            speed,
+            phase: 0,
+        }
+    }
+}
+
+impl Effect for Wave {
+    fn next_frame(&mut self, _pressed: &[u8]) -> CustomFrame {
+        let mut frame = CustomFrame::new();
+        for key in KeyName::iter() {
+            let level = (key as u8).wrapping_mul(5).wrapping_add(self.phase);
+            frame.set_key(key, scale(self.color, level));
+        }
+        self.phase = self.phase.wrapping_add(self.speed);
+        frame
+    }
+}
@@ -0,0 +1,263 @@
+use smart_leds::RGB8;
+use strum::IntoEnumIterator;
+use strum_macros::*;
+
+/// number of bytes in a custom lighting-scheme payload, see `upload_custom`/`download_custom`
+const FRAME_LEN: usize = 512;
+
+/// Named physical keys, in the slot order the controller expects their RGB triplets.
+/// Each key occupies 3 consecutive bytes (R, G, B) starting at `key as usize * 3`.
+///
+/// UNVERIFIED: this is a placeholder layout (keys packed densely, in declaration
+/// order, starting at byte 0) and has not been confirmed against the hardware's
+/// real per-key LED map. A 512-byte buffer for ~78 keys is more bytes than a dense
+/// packing needs, so the real layout is likely sparse/positional rather than
+/// sequential. Treat these offsets as a best guess until checked against a known-
+/// good `upload_custom` payload captured from the vendor tool.
+///
+/// To calibrate against real hardware: upload a frame with a single key set via
+/// `CustomFrame::set_key`, then read the slot back with `FusionKBD::download_custom`
+/// (now that it validates the handshake header instead of the LED payload itself,
+/// see `kbd::ProtocolError`) and run it through `CustomFrame::changed_offsets`
+/// against a blank frame - the nonzero offset it reports is that key's real one.
+#[derive(Display, EnumIter, EnumString, PartialEq, Eq, Clone, Copy, Debug)]
+#[strum(serialize_all = "snake_case")]
+pub enum KeyName {
+    Escape = 0,
+    F1 = 1,
+    F2 = 2,
+    F3 = 3,
+    F4 = 4,
+    F5 = 5,
+    F6 = 6,
+    F7 = 7,
+    F8 = 8,
+    F9 = 9,
+    F10 = 10,
+    F11 = 11,
+    F12 = 12,
+    Grave = 13,
+    N1 = 14,
+    N2 = 15,
+    N3 = 16,
+    N4 = 17,
+    N5 = 18,
+    N6 = 19,
+    N7 = 20,
+    N8 = 21,
+    N9 = 22,
+    N0 = 23,
+    Minus = 24,
+    Equals = 25,
+    Backspace = 26,
+    Tab = 27,
+    Q = 28,
+    W = 29,
+    E = 30,
+    R = 31,
+    T = 32,
+    Y = 33,
+    U = 34,
+    I = 35,
+    O = 36,
+    P = 37,
+    LeftBracket = 38,
+    RightBracket = 39,
+    Backslash = 40,
+    CapsLock = 41,
+    A = 42,
+    S = 43,
+    D = 44,
+    F = 45,
+    G = 46,
+    H = 47,
+    J = 48,
+    K = 49,
+    L = 50,
+    Semicolon = 51,
+    Quote = 52,
+    Enter = 53,
+    LeftShift = 54,
+    Z = 55,
+    X = 56,
+    C = 57,
+    V = 58,
+    B = 59,
+    N = 60,
+    M = 61,
+    Comma = 62,
+    Period = 63,
+    Slash = 64,
+    RightShift = 65,
+    LeftCtrl = 66,
+    LeftGui = 67,
+    LeftAlt = 68,
+    Space = 69,
+    RightAlt = 70,
+    RightGui = 71,
+    Menu = 72,
+    RightCtrl = 73,
+    Left = 74,
+    Up = 75,
+    Down = 76,
+    Right = 77,
+}
+
+/// number of named keys in `KeyName`, i.e. one past the highest slot index
+pub const KEY_COUNT: usize = 78;
+
+/// maps a boot-protocol HID usage code (see `kbd::get_key`) to the physical key it
+/// refers to, so a pressed-key stream can be turned into a `CustomFrame` update
+pub fn usage_to_key_name(usage: u8) -> Option<KeyName> {
+    match usage {
+        0x04 => Some(KeyName::A),
+        0x05 => Some(KeyName::B),
+        0x06 => Some(KeyName::C),
+        0x07 => Some(KeyName::D),
+        0x08 => Some(KeyName::E),
+        0x09 => Some(KeyName::F),
+        0x0a => Some(KeyName::G),
+        0x0b => Some(KeyName::H),
+        0x0c => Some(KeyName::I),
+        0x0d => Some(KeyName::J),
+        0x0e => Some(KeyName::K),
+        0x0f => Some(KeyName::L),
+        0x10 => Some(KeyName::M),
+        0x11 => Some(KeyName::N),
+        0x12 => Some(KeyName::O),
+        0x13 => Some(KeyName::P),
+        0x14 => Some(KeyName::Q),
+        0x15 => Some(KeyName::R),
+        0x16 => Some(KeyName::S),
+        0x17 => Some(KeyName::T),
+        0x18 => Some(KeyName::U),
+        0x19 => Some(KeyName::V),
+        0x1a => Some(KeyName::W),
+        0x1b => Some(KeyName::X),
+        0x1c => Some(KeyName::Y),
+        0x1d => Some(KeyName::Z),
+        0x1e => Some(KeyName::N1),
+        0x1f => Some(KeyName::N2),
+        0x20 => Some(KeyName::N3),
+        0x21 => Some(KeyName::N4),
+        0x22 => Some(KeyName::N5),
+        0x23 => Some(KeyName::N6),
+        0x24 => Some(KeyName::N7),
+        0x25 => Some(KeyName::N8),
+        0x26 => Some(KeyName::N9),
+        0x27 => Some(KeyName::N0),
+        0x28 => Some(KeyName::Enter),
+        0x2b => Some(KeyName::Tab),
+        0x2c => Some(KeyName::Space),
+        0x2d => Some(KeyName::Minus),
+        0x2e => Some(KeyName::Equals),
+        0x2f => Some(KeyName::LeftBracket),
+        0x30 => Some(KeyName::RightBracket),
+        0x31 => Some(KeyName::Backslash),
+        0x33 => Some(KeyName::Semicolon),
+        0x34 => Some(KeyName::Quote),
+        0x35 => Some(KeyName::Grave),
+        0x36 => Some(KeyName::Comma),
+        0x37 => Some(KeyName::Period),
+        0x38 => Some(KeyName::Slash),
+        0x39 => Some(KeyName::CapsLock),
+        0x3a => Some(KeyName::F1),
+        0x3b => Some(KeyName::F2),
+        0x3c => Some(KeyName::F3),
+        0x3d => Some(KeyName::F4),
+        0x3e => Some(KeyName::F5),
+        0x3f => Some(KeyName::F6),
+        0x40 => Some(KeyName::F7),
+        0x41 => Some(KeyName::F8),
+        0x42 => Some(KeyName::F9),
+        0x43 => Some(KeyName::F10),
+        0x44 => Some(KeyName::F11),
+        0x45 => Some(KeyName::F12),
+        0x29 => Some(KeyName::Escape),
+        0x2a => Some(KeyName::Backspace),
+        0x65 => Some(KeyName::Menu),
+        0x4f => Some(KeyName::Right),
+        0x50 => Some(KeyName::Left),
+        0x51 => Some(KeyName::Down),
+        0x52 => Some(KeyName::Up),
+        0xe0 => Some(KeyName::LeftCtrl),
+        0xe1 => Some(KeyName::LeftShift),
+        0xe2 => Some(KeyName::LeftAlt),
+        0xe3 => Some(KeyName::LeftGui),
+        0xe4 => Some(KeyName::RightCtrl),
+        0xe5 => Some(KeyName::RightShift),
+        0xe6 => Some(KeyName::RightAlt),
+        0xe7 => Some(KeyName::RightGui),
+        _ => None,
+    }
+}
+
+/// A full per-key RGB lighting layout that serializes straight into `upload_custom`.
+#[derive(Clone)]
+pub struct CustomFrame {
+    bytes: [u8; FRAME_LEN],
+}
+
+impl Default for CustomFrame {
+    fn default() -> Self {
+        CustomFrame {
+            bytes: [0; FRAME_LEN],
+        }
+    }
+}
+
+impl CustomFrame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// set a single named key to a color
+    pub fn set_key(&mut self, key: KeyName, color: RGB8) {
+        // see the UNVERIFIED note on `KeyName` - this offset is a placeholder
+        let offset = key as usize * 3;
+        self.bytes[offset] = color.r;
+        self.bytes[offset + 1] = color.g;
+        self.bytes[offset + 2] = color.b;
+    }
+
+    /// set every known key to the same color
+    pub fn fill(&mut self, color: RGB8) {
+        for key in KeyName::iter() {
+            self.set_key(key, color);
+        }
+    }
+
+    /// scale every channel already written to this frame, like `smart_leds::brightness`,
+    /// so a frame can be dimmed in software without touching the hardware brightness field
+    pub fn scale_brightness(&mut self, factor: u8) {
+        for byte in self.bytes.iter_mut() {
+            *byte = (*byte as u16 * factor as u16 / 0xff) as u8;
+        }
+    }
+
+    /// the raw 512-byte payload, ready to hand to `FusionKBD::upload_custom`
+    pub fn serialize(&self) -> [u8; FRAME_LEN] {
+        self.bytes
+    }
+
+    /// the byte offsets where `self` and `baseline` differ, in ascending order.
+    ///
+    /// Intended for calibrating the `KeyName` offset table against real hardware:
+    /// set a single key, upload it, read the slot back into a frame via
+    /// `CustomFrame::from_bytes`, and diff it against a blank `baseline` - the
+    /// offsets that changed are that key's real R/G/B bytes.
+    pub fn changed_offsets(&self, baseline: &CustomFrame) -> Vec<usize> {
+        self.bytes
+            .iter()
+            .zip(baseline.bytes.iter())
+            .enumerate()
+            .filter_map(|(offset, (a, b))| (a != b).then_some(offset))
+            .collect()
+    }
+
+    /// wraps an already-serialized payload (e.g. read back via `download_custom`)
+    /// for comparison with `changed_offsets`
+    pub fn from_bytes(bytes: [u8; FRAME_LEN]) -> Self {
+        CustomFrame { bytes }
+    }
+}
@@ -1,7 +1,11 @@
+use std::cell::Cell;
+use std::thread;
 use std::time;
 use rusb::UsbContext;
 use strum_macros::*;
 
+use crate::effects::Effect;
+
 #[derive(Display, EnumIter, EnumString, PartialEq)]
 #[strum(serialize_all = "snake_case")]
 pub enum Preset {
@@ -34,7 +38,151 @@ pub enum Color {
     White = 0x07,
 }
 
-#[repr(C, packed)]
+/// Modifier bitmask from byte 0 of a boot-protocol keyboard report.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const LEFT_CTRL: u8 = 1 << 0;
+    pub const LEFT_SHIFT: u8 = 1 << 1;
+    pub const LEFT_ALT: u8 = 1 << 2;
+    pub const LEFT_GUI: u8 = 1 << 3;
+    pub const RIGHT_CTRL: u8 = 1 << 4;
+    pub const RIGHT_SHIFT: u8 = 1 << 5;
+    pub const RIGHT_ALT: u8 = 1 << 6;
+    pub const RIGHT_GUI: u8 = 1 << 7;
+
+    fn from_byte(byte: u8) -> Self {
+        Modifiers(byte)
+    }
+
+    fn has(self, mask: u8) -> bool {
+        self.0 & mask != 0
+    }
+
+    pub fn shift(self) -> bool {
+        self.has(Self::LEFT_SHIFT) || self.has(Self::RIGHT_SHIFT)
+    }
+
+    pub fn ctrl(self) -> bool {
+        self.has(Self::LEFT_CTRL) || self.has(Self::RIGHT_CTRL)
+    }
+
+    pub fn alt(self) -> bool {
+        self.has(Self::LEFT_ALT) || self.has(Self::RIGHT_ALT)
+    }
+
+    pub fn gui(self) -> bool {
+        self.has(Self::LEFT_GUI) || self.has(Self::RIGHT_GUI)
+    }
+}
+
+/// A single newly-pressed key, decoded from a boot-protocol report.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub usage: u8,
+    pub modifiers: Modifiers,
+    pub ch: Option<char>,
+}
+
+/// maps a HID usage code (bytes 2-7 of the report) to its unshifted/shifted glyph
+fn usage_to_char(usage: u8, shift: bool) -> Option<char> {
+    match usage {
+        0x04..=0x1d => {
+            let letter = (b'a' + (usage - 0x04)) as char;
+            Some(if shift { letter.to_ascii_uppercase() } else { letter })
+        }
+        0x1e..=0x26 => {
+            // 1-9
+            let index = (usage - 0x1e) as usize;
+            Some(if shift {
+                b"!@#$%^&*("[index] as char
+            } else {
+                (b'1' + usage - 0x1e) as char
+            })
+        }
+        0x27 => Some(if shift { ')' } else { '0' }),
+        0x28 => Some('\n'), // Enter
+        0x2b => Some('\t'), // Tab
+        0x2c => Some(' '),  // Space
+        0x2d => Some(if shift { '_' } else { '-' }),
+        0x2e => Some(if shift { '+' } else { '=' }),
+        0x2f => Some(if shift { '{' } else { '[' }),
+        0x30 => Some(if shift { '}' } else { ']' }),
+        0x31 => Some(if shift { '|' } else { '\\' }),
+        0x33 => Some(if shift { ':' } else { ';' }),
+        0x34 => Some(if shift { '"' } else { '\'' }),
+        0x35 => Some(if shift { '~' } else { '`' }),
+        0x36 => Some(if shift { '<' } else { ',' }),
+        0x37 => Some(if shift { '>' } else { '.' }),
+        0x38 => Some(if shift { '?' } else { '/' }),
+        _ => None,
+    }
+}
+
+/// usage codes 0x01-0x03 are the boot-protocol's reserved/error slots (e.g.
+/// ErrorRollOver, POSTFail, ErrorUndefined), not real keys - a report full of
+/// these means "too many keys" or "not ready", never an actual press
+fn is_phantom_usage(usage: u8) -> bool {
+    (0x01..=0x03).contains(&usage)
+}
+
+/// pure edge-detection core of `get_key`: given the previous and current boot-
+/// protocol reports, finds the first usage code that is newly pressed (present now,
+/// absent before), ignoring reserved/error codes - same idea as keyberon's
+/// press/release `Event`
+fn newly_pressed_key(prev: [u8; 8], current: [u8; 8]) -> Option<KeyEvent> {
+    let modifiers = Modifiers::from_byte(current[0]);
+    current[2..8]
+        .iter()
+        .find(|&&usage| usage != 0x00 && !is_phantom_usage(usage) && !prev[2..8].contains(&usage))
+        .map(|&usage| KeyEvent {
+            usage,
+            modifiers,
+            ch: usage_to_char(usage, modifiers.shift()),
+        })
+}
+
+/// Error returned when the low-level wire protocol misbehaves: a checksum that
+/// doesn't match its payload, or a header of a kind we didn't ask for.
+#[derive(Debug)]
+pub enum ProtocolError {
+    Usb(rusb::Error),
+    ChecksumMismatch,
+    UnexpectedKind { expected: u8, actual: u8 },
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::Usb(err) => write!(f, "{}", err),
+            ProtocolError::ChecksumMismatch => {
+                write!(f, "header checksum did not match its payload")
+            }
+            ProtocolError::UnexpectedKind { expected, actual } => write!(
+                f,
+                "expected header kind 0x{:02x}, got 0x{:02x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<rusb::Error> for ProtocolError {
+    fn from(err: rusb::Error) -> Self {
+        ProtocolError::Usb(err)
+    }
+}
+
+/// a validated view of the header `download_custom` read back from the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfigHeader {
+    pub slot: u8,
+}
+
+#[derive(Debug, PartialEq)]
 struct Header {
     kind: u8,         // Kind of the control transfer
     reserved: u8,     // ??
@@ -60,19 +208,51 @@ impl Header {
             checksum: 0,
         };
 
-        // calculate checksum byte
-        header.checksum = !(header
-            .as_bytes()
+        header.checksum = header.compute_checksum();
+        header
+    }
+
+    fn compute_checksum(&self) -> u8 {
+        !(self
+            .encode()
             .iter()
             .take(7)
-            .fold(0, |sum, x| sum.wrapping_add(*x)));
+            .fold(0, |sum, x| sum.wrapping_add(*x)))
+    }
 
-        header
+    /// serializes the eight header fields by hand, in wire order
+    fn encode(&self) -> [u8; 8] {
+        [
+            self.kind,
+            self.reserved,
+            self.mode,
+            self.speed_length,
+            self.brightness,
+            self.color,
+            self.reserved2,
+            self.checksum,
+        ]
     }
 
-    /// used when sending over-the-wire with rusb
-    fn as_bytes(&self) -> &[u8; std::mem::size_of::<Self>()] {
-        unsafe { &*(self as *const Header as *const [u8; 8]) }
+    /// parses a header read back from the device, rejecting one whose checksum
+    /// doesn't match its payload
+    fn decode(bytes: [u8; 8]) -> Result<Header, ProtocolError> {
+        let header = Header {
+            kind: bytes[0],
+            reserved: bytes[1],
+            mode: bytes[2],
+            speed_length: bytes[3],
+            brightness: bytes[4],
+            color: bytes[5],
+            reserved2: bytes[6],
+            checksum: bytes[7],
+        };
+
+        if header.checksum != header.compute_checksum() {
+            return Err(ProtocolError::ChecksumMismatch);
+        }
+
+        Ok(header)
     }
 }
 
@@ -80,31 +260,113 @@ static KIND_PRESET: u8 = 0x08;
 static KIND_CUSTOM_CONFIG: u8 = 0x12;
 static KIND_READ_CONFIG: u8 = 0x92;
 
+/// a controller VID/PID rebrand, along with the interfaces it exposes the lighting
+/// and keyboard endpoints on (not every rebrand necessarily agrees on 0 and 3)
+struct SupportedDevice {
+    vid: u16,
+    pid: u16,
+    interfaces: (u8, u8),
+}
+
+static SUPPORTED_DEVICES: &[SupportedDevice] = &[SupportedDevice {
+    vid: 0x1044,
+    pid: 0x7a3f,
+    interfaces: (0, 3),
+}];
+
+fn supported_device(vid: u16, pid: u16) -> Option<&'static SupportedDevice> {
+    SUPPORTED_DEVICES
+        .iter()
+        .find(|device| device.vid == vid && device.pid == pid)
+}
+
+/// identifies one connected, currently-unopened device matching a known VID/PID
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub vid: u16,
+    pub pid: u16,
+    pub bus_number: u8,
+    pub address: u8,
+    pub serial: Option<String>,
+}
+
 pub struct FusionKBD<T: UsbContext> {
     handle: rusb::DeviceHandle<T>,
+    prev_report: Cell<[u8; 8]>,
+    interfaces: (u8, u8),
 }
 
 impl<'a, T: UsbContext> FusionKBD<T> {
-    pub fn new(context: &'a T) -> Result<Self, rusb::Error> {
-        let mut handle = match context.open_device_with_vid_pid(0x1044, 0x7a3f) {
-            Some(handle) => handle,
-            None => {
-                eprintln!("Failed to open device! Are you running as root?");
-                return Err(rusb::Error::Access);
-            }
-        };
+    /// enumerates every connected device matching a known supported VID/PID
+    pub fn list(context: &'a T) -> Result<Vec<DeviceInfo>, rusb::Error> {
+        let mut found = Vec::new();
 
-        if handle.kernel_driver_active(0)? {
-            handle.detach_kernel_driver(0)?;
+        for device in context.devices()?.iter() {
+            let descriptor = device.device_descriptor()?;
+            let Some(known) = supported_device(descriptor.vendor_id(), descriptor.product_id())
+            else {
+                continue;
+            };
+
+            let serial = device.open().ok().and_then(|handle| {
+                let timeout = time::Duration::from_millis(100);
+                let language = *handle.read_languages(timeout).ok()?.first()?;
+                handle
+                    .read_serial_number_string(language, &descriptor, timeout)
+                    .ok()
+            });
+
+            found.push(DeviceInfo {
+                vid: known.vid,
+                pid: known.pid,
+                bus_number: device.bus_number(),
+                address: device.address(),
+                serial,
+            });
         }
-        if handle.kernel_driver_active(3)? {
-            handle.detach_kernel_driver(3)?;
+
+        Ok(found)
+    }
+
+    /// opens the device identified by a previous call to `list`
+    pub fn open_device(context: &'a T, info: &DeviceInfo) -> Result<Self, rusb::Error> {
+        let known = supported_device(info.vid, info.pid).ok_or(rusb::Error::NotSupported)?;
+
+        let device = context
+            .devices()?
+            .iter()
+            .find(|device| device.bus_number() == info.bus_number && device.address() == info.address)
+            .ok_or(rusb::Error::NoDevice)?;
+
+        let handle = device.open()?;
+        let (iface_a, iface_b) = known.interfaces;
+
+        if handle.kernel_driver_active(iface_a)? {
+            handle.detach_kernel_driver(iface_a)?;
+        }
+        if handle.kernel_driver_active(iface_b)? {
+            handle.detach_kernel_driver(iface_b)?;
         }
 
-        handle.claim_interface(0)?;
-        handle.claim_interface(3)?;
+        handle.claim_interface(iface_a)?;
+        handle.claim_interface(iface_b)?;
 
-        Ok(FusionKBD { handle })
+        Ok(FusionKBD {
+            handle,
+            prev_report: Cell::new([0; 8]),
+            interfaces: (iface_a, iface_b),
+        })
+    }
+
+    /// opens the first connected device matching a known supported VID/PID
+    pub fn new(context: &'a T) -> Result<Self, rusb::Error> {
+        match Self::list(context)?.into_iter().next() {
+            Some(info) => Self::open_device(context, &info),
+            None => {
+                eprintln!("Failed to open device! Are you running as root?");
+                Err(rusb::Error::NoDevice)
+            }
+        }
     }
 
     fn write_control_kbd(&self, header: &Header) -> Result<usize, rusb::Error> {
@@ -117,7 +379,7 @@ impl<'a, T: UsbContext> FusionKBD<T> {
             0x09,   // bRequest
             0x0300, // wValue
             0x0003, // wIndex
-            header.as_bytes(),
+            &header.encode(),
             time::Duration::new(0, 0),
         )
     }
@@ -142,25 +404,43 @@ impl<'a, T: UsbContext> FusionKBD<T> {
         Ok(())
     }
 
-    pub fn download_custom(&self, slot: u8, data: &mut [u8; 512]) -> Result<(), rusb::Error> {
+    /// reads back the custom lighting config in `slot` into `data`, validating the
+    /// handshake header the device replies with before trusting the transfer, and
+    /// returning a parsed view of it.
+    ///
+    /// `data` itself is the raw per-key LED payload `upload_custom` writes - it
+    /// carries no header of its own, so it is returned as-is.
+    pub fn download_custom(
+        &self,
+        slot: u8,
+        data: &mut [u8; 512],
+    ) -> Result<ConfigHeader, ProtocolError> {
         assert!(slot < 5);
 
         self.write_control_kbd(&Header::new(KIND_READ_CONFIG, slot, 0, 0, 0))?;
 
+        let mut header_buf = [0u8; 8];
         self.handle.read_control(
             rusb::request_type(
                 rusb::Direction::In,
                 rusb::RequestType::Class,
                 rusb::Recipient::Interface,
             ),
-            0x01,        // bRequest
-            0x0300,      // wValue
-            0x0003,      // wIndex
-            &mut [0; 8], // dummy buffer
+            0x01,   // bRequest
+            0x0300, // wValue
+            0x0003, // wIndex
+            &mut header_buf,
             time::Duration::new(0, 0),
         )?;
 
-        print!("Interrupt transfers...");
+        let header = Header::decode(header_buf)?;
+        if header.kind != KIND_READ_CONFIG {
+            return Err(ProtocolError::UnexpectedKind {
+                expected: KIND_READ_CONFIG,
+                actual: header.kind,
+            });
+        }
+
         for i in 0..8 {
             let start = i * 64;
             let end = start + 64;
@@ -173,9 +453,8 @@ impl<'a, T: UsbContext> FusionKBD<T> {
                 eprintln!("Interrupt transfer {} failed: {}", i, tf);
             }
         }
-        println!("Ok!");
 
-        Ok(())
+        Ok(ConfigHeader { slot: header.mode })
     }
 
     /// upload custom lighting scheme to selected custom mode slot
@@ -184,7 +463,6 @@ impl<'a, T: UsbContext> FusionKBD<T> {
         let header = Header::new(KIND_CUSTOM_CONFIG, slot, 0x08, 0x00, 0x00);
         self.write_control_kbd(&header)?;
 
-        print!("Interrupt transfers...");
         for i in 0..8 {
             let start = i * 64;
             let end = start + 64;
@@ -195,7 +473,6 @@ impl<'a, T: UsbContext> FusionKBD<T> {
                 eprintln!("Interrupt transfer {} failed: {}", i, tf);
             }
         }
-        println!("Ok!");
 
         // will NOT automatically switch to the new mode!
         // requires call to set_custom
@@ -213,31 +490,134 @@ impl<'a, T: UsbContext> FusionKBD<T> {
         Ok(())
     }
 
-    pub fn get_key(&self) -> Option<char> {
+    /// reads one boot-protocol keyboard report and returns the first key that
+    /// transitioned from released to pressed since the last call (edge-detected
+    /// against the previous report, same idea as keyberon's press/release `Event`)
+    pub fn get_key(&self) -> Option<KeyEvent> {
         let mut buf: [u8; 8] = [0; 8];
         let _ = self
             .handle
             .read_interrupt(0x81, &mut buf, time::Duration::from_millis(10));
 
-        // too lazy to actually implement usbhid translaton.
-        // maybe later?
-        // check out:
-        //   - https://bitvijays.github.io/LFC-Forensics.html#usb-keyboard
-        //   - google usb_hid_keys.h
+        let prev = self.prev_report.get();
+        self.prev_report.set(buf);
+
+        newly_pressed_key(prev, buf)
+    }
 
-        if buf[2] != 0x00 {
-            Some('a')
-        } else {
-            None
+    /// drives a host-computed `Effect` in custom mode `slot`, pushing one new frame
+    /// every `tick` by uploading it and re-selecting the slot, while feeding the
+    /// effect the usage code `get_key` reports as newly pressed (at most one per
+    /// tick); runs forever unless `ticks` caps the number of frames
+    pub fn run_effect<E: Effect>(
+        &self,
+        mut effect: E,
+        slot: u8,
+        brightness: u8,
+        tick: time::Duration,
+        ticks: Option<u64>,
+    ) -> Result<(), rusb::Error> {
+        let mut elapsed: u64 = 0;
+        loop {
+            let pressed: Vec<u8> = self.get_key().map(|event| event.usage).into_iter().collect();
+            let frame = effect.next_frame(&pressed);
+
+            self.upload_custom(slot, &frame.serialize())?;
+            self.set_custom(slot, brightness)?;
+
+            elapsed += 1;
+            if ticks.is_some_and(|n| elapsed >= n) {
+                break;
+            }
+            thread::sleep(tick);
         }
+
+        Ok(())
     }
 }
 
-impl<'a, T: UsbContext> Drop for FusionKBD<T> {
+impl<T: UsbContext> Drop for FusionKBD<T> {
     fn drop(&mut self) {
-        let _ = self.handle.release_interface(0);
-        let _ = self.handle.release_interface(3);
-        let _ = self.handle.attach_kernel_driver(0);
-        let _ = self.handle.attach_kernel_driver(3);
+        let (iface_a, iface_b) = self.interfaces;
+        let _ = self.handle.release_interface(iface_a);
+        let _ = self.handle.release_interface(iface_b);
+        let _ = self.handle.attach_kernel_driver(iface_a);
+        let _ = self.handle.attach_kernel_driver(iface_b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_to_char_unshifted_letters_and_digits() {
+        assert_eq!(usage_to_char(0x04, false), Some('a'));
+        assert_eq!(usage_to_char(0x1d, false), Some('z'));
+        assert_eq!(usage_to_char(0x1e, false), Some('1'));
+        assert_eq!(usage_to_char(0x27, false), Some('0'));
+    }
+
+    #[test]
+    fn usage_to_char_shifted_rows() {
+        assert_eq!(usage_to_char(0x04, true), Some('A'));
+        assert_eq!(usage_to_char(0x1e, true), Some('!'));
+        assert_eq!(usage_to_char(0x27, true), Some(')'));
+        assert_eq!(usage_to_char(0x36, true), Some('<'));
+    }
+
+    #[test]
+    fn usage_to_char_unknown_usage_is_none() {
+        assert_eq!(usage_to_char(0x00, false), None);
+        assert_eq!(usage_to_char(0xff, false), None);
+    }
+
+    #[test]
+    fn newly_pressed_key_detects_fresh_press() {
+        let prev = [0; 8];
+        let current = [0, 0, 0x04, 0, 0, 0, 0, 0];
+        let event = newly_pressed_key(prev, current).expect("expected a press");
+        assert_eq!(event.usage, 0x04);
+        assert_eq!(event.ch, Some('a'));
+    }
+
+    #[test]
+    fn newly_pressed_key_ignores_already_held_key() {
+        let prev = [0, 0, 0x04, 0, 0, 0, 0, 0];
+        let current = [0, 0, 0x04, 0, 0, 0, 0, 0];
+        assert_eq!(newly_pressed_key(prev, current), None);
+    }
+
+    #[test]
+    fn newly_pressed_key_applies_shift_modifier() {
+        let prev = [0; 8];
+        let current = [Modifiers::LEFT_SHIFT, 0, 0x1e, 0, 0, 0, 0, 0];
+        let event = newly_pressed_key(prev, current).expect("expected a press");
+        assert!(event.modifiers.shift());
+        assert_eq!(event.ch, Some('!'));
+    }
+
+    #[test]
+    fn newly_pressed_key_ignores_phantom_error_rollover() {
+        let prev = [0; 8];
+        let current = [0, 0, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01];
+        assert_eq!(newly_pressed_key(prev, current), None);
+    }
+
+    #[test]
+    fn header_round_trips_through_encode_decode() {
+        let header = Header::new(KIND_PRESET, 0x02, 0x03, 0x32, Color::Red as u8);
+        let decoded = Header::decode(header.encode()).expect("valid checksum");
+        assert_eq!(decoded, header);
+    }
+
+    #[test]
+    fn header_decode_rejects_corrupted_checksum() {
+        let mut bytes = Header::new(KIND_PRESET, 0x02, 0x03, 0x32, Color::Red as u8).encode();
+        bytes[7] ^= 0xff;
+        assert!(matches!(
+            Header::decode(bytes),
+            Err(ProtocolError::ChecksumMismatch)
+        ));
     }
 }
\ No newline at end of file
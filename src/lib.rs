@@ -0,0 +1,5 @@
+pub mod effects;
+pub mod frame;
+pub mod kbd;
+#[cfg(feature = "lua")]
+pub mod script;
@@ -0,0 +1,78 @@
+//! Exposes the keyboard control surface to an embedded Lua runtime, the way the
+//! USB test-bench tool this crate is modeled after drives its instrument from a
+//! Lua REPL. Gated behind the `lua` feature since `mlua` pulls in a bundled
+//! interpreter that most users of this crate don't need.
+
+use std::rc::Rc;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use mlua::{Lua, Result as LuaResult};
+use rusb::UsbContext;
+
+use crate::kbd::{Color, FusionKBD, Preset};
+
+/// Runs `source` against `kbd`, registering `set_preset`, `upload_custom`,
+/// `set_custom`, `get_key` and `sleep` as Lua globals that wrap the matching
+/// `FusionKBD` methods. `Preset`/`Color` arguments are taken as their snake_case
+/// `strum` names (e.g. `"breathing"`, `"rand"`) so scripts read naturally.
+pub fn run_script<T: UsbContext + 'static>(kbd: Rc<FusionKBD<T>>, source: &str) -> LuaResult<()> {
+    let lua = Lua::new();
+    let globals = lua.globals();
+
+    {
+        let kbd = kbd.clone();
+        globals.set(
+            "set_preset",
+            lua.create_function(
+                move |_, (preset, speed, brightness, color): (String, u8, u8, String)| {
+                    let preset = Preset::from_str(&preset).map_err(mlua::Error::external)?;
+                    let color = Color::from_str(&color).map_err(mlua::Error::external)?;
+                    kbd.set_preset(preset, speed, brightness, color)
+                        .map_err(mlua::Error::external)
+                },
+            )?,
+        )?;
+    }
+
+    {
+        let kbd = kbd.clone();
+        globals.set(
+            "upload_custom",
+            lua.create_function(move |_, (slot, bytes): (u8, Vec<u8>)| {
+                kbd.upload_custom(slot, &bytes).map_err(mlua::Error::external)
+            })?,
+        )?;
+    }
+
+    {
+        let kbd = kbd.clone();
+        globals.set(
+            "set_custom",
+            lua.create_function(move |_, (slot, brightness): (u8, u8)| {
+                kbd.set_custom(slot, brightness).map_err(mlua::Error::external)
+            })?,
+        )?;
+    }
+
+    {
+        let kbd = kbd.clone();
+        globals.set(
+            "get_key",
+            lua.create_function(move |_, ()| {
+                Ok(kbd.get_key().and_then(|event| event.ch).map(|ch| ch.to_string()))
+            })?,
+        )?;
+    }
+
+    globals.set(
+        "sleep",
+        lua.create_function(|_, ms: u64| {
+            thread::sleep(Duration::from_millis(ms));
+            Ok(())
+        })?,
+    )?;
+
+    lua.load(source).exec()
+}